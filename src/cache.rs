@@ -0,0 +1,148 @@
+use std::{fs, io, path::PathBuf};
+
+use directories::ProjectDirs;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ContentCacheError {
+    #[error("could not get base project dir")]
+    ProjectDir,
+    #[error("cid {0:?} is not a valid cache key")]
+    InvalidCid(String),
+    #[error("could not create cache directory {0:?}: {1:?}")]
+    CreateDir(PathBuf, #[source] io::Error),
+    #[error("could not write cache entry {0:?}: {1:?}")]
+    Write(PathBuf, #[source] io::Error),
+    #[error("could not read cache directory {0:?}: {1:?}")]
+    ReadDir(PathBuf, #[source] io::Error),
+}
+
+fn validate_cid(cid: &str) -> Result<(), ContentCacheError> {
+    if !cid.is_empty() && cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(())
+    } else {
+        Err(ContentCacheError::InvalidCid(cid.to_owned()))
+    }
+}
+
+pub struct ContentCache {
+    dir: PathBuf,
+    max_entries: Option<usize>,
+}
+
+impl ContentCache {
+    pub fn builder<S: AsRef<str>>(app_name: S) -> ContentCacheBuilder<S> {
+        ContentCacheBuilder::new(app_name)
+    }
+
+    pub async fn get(&self, cid: &str) -> Option<Vec<u8>> {
+        let path = self.entry_path(cid).ok()?;
+
+        tokio::task::spawn_blocking(move || fs::read(path).ok())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn put(&self, cid: &str, bytes: &[u8]) -> Result<(), ContentCacheError> {
+        let path = self.entry_path(cid)?;
+        let dir = self.dir.clone();
+        let bytes = bytes.to_vec();
+        let max_entries = self.max_entries;
+
+        tokio::task::spawn_blocking(move || {
+            fs::create_dir_all(&dir).map_err(|err| ContentCacheError::CreateDir(dir.clone(), err))?;
+            fs::write(&path, &bytes).map_err(|err| ContentCacheError::Write(path, err))?;
+
+            if let Some(max_entries) = max_entries {
+                evict_beyond(&dir, max_entries)?;
+            }
+
+            Ok(())
+        })
+        .await
+        .expect("cache put blocking task panicked")
+    }
+
+    fn entry_path(&self, cid: &str) -> Result<PathBuf, ContentCacheError> {
+        validate_cid(cid)?;
+        Ok(self.dir.join(cid))
+    }
+}
+
+fn evict_beyond(dir: &PathBuf, max_entries: usize) -> Result<(), ContentCacheError> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map_err(|err| ContentCacheError::ReadDir(dir.clone(), err))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((entry.path(), modified))
+        })
+        .collect();
+
+    if entries.len() <= max_entries {
+        return Ok(());
+    }
+
+    entries.sort_by_key(|(_, modified)| *modified);
+    let evict_count = entries.len() - max_entries;
+    for (path, _) in entries.into_iter().take(evict_count) {
+        let _ = fs::remove_file(path);
+    }
+
+    Ok(())
+}
+
+pub struct ContentCacheBuilder<S: AsRef<str>> {
+    app_name: S,
+    dir: Option<PathBuf>,
+    max_entries: Option<usize>,
+    disabled: bool,
+}
+
+impl<S: AsRef<str>> ContentCacheBuilder<S> {
+    pub fn new(app_name: S) -> Self {
+        Self {
+            app_name,
+            dir: None,
+            max_entries: None,
+            disabled: false,
+        }
+    }
+
+    pub fn dir(mut self, dir: PathBuf) -> Self {
+        self.dir = Some(dir);
+        self
+    }
+
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = disabled;
+        self
+    }
+
+    pub fn build(self) -> Result<Option<ContentCache>, ContentCacheError> {
+        if self.disabled {
+            return Ok(None);
+        }
+
+        let dir = if let Some(dir) = self.dir {
+            dir
+        } else if let Some(project_dirs) =
+            ProjectDirs::from("xyz", "carrot-labs", self.app_name.as_ref())
+        {
+            project_dirs.cache_dir().join("cid")
+        } else {
+            return Err(ContentCacheError::ProjectDir);
+        };
+
+        Ok(Some(ContentCache {
+            dir,
+            max_entries: self.max_entries,
+        }))
+    }
+}
@@ -1,11 +1,17 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use backoff::{future::retry, ExponentialBackoff};
-use reqwest::Method;
+use reqwest::{Method, StatusCode};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::http_client::{HttpClient, HttpClientError};
+use crate::{
+    cache::ContentCache,
+    http_client::{
+        is_permanent_client_error, is_permanent_status, response_retry_after, HttpClient,
+        HttpClientError,
+    },
+};
 
 #[derive(Error, Debug)]
 pub enum FetchJsonError {
@@ -13,15 +19,19 @@ pub enum FetchJsonError {
     RequestConstruction(#[source] HttpClientError),
     #[error("error while performing json fetching request: {0:?}")]
     Request(#[source] reqwest::Error),
+    #[error("json fetch request returned non-success status {0}")]
+    Status(StatusCode, Option<Duration>),
+    #[error("error while reading json fetch response body: {0:?}")]
+    ResponseBody(#[source] reqwest::Error),
     #[error("error while deserializing json fetch response: {0:?}")]
-    Deserialization(#[source] reqwest::Error),
+    Deserialization(#[source] serde_json::Error),
 }
 
-async fn fetch_json<J: DeserializeOwned>(
+async fn fetch_bytes(
     cid: String,
     s3_cdn_http_client: Arc<HttpClient>,
     ipfs_http_client: Arc<HttpClient>,
-) -> Result<J, FetchJsonError> {
+) -> Result<Vec<u8>, FetchJsonError> {
     let cid = cid.to_lowercase();
 
     match s3_cdn_http_client
@@ -32,21 +42,40 @@ async fn fetch_json<J: DeserializeOwned>(
         .await
     {
         Ok(res) => {
+            if let Err(status_err) = res.error_for_status_ref() {
+                return Err(FetchJsonError::Status(
+                    status_err.status().unwrap_or(res.status()),
+                    response_retry_after(&res),
+                ));
+            }
+
             return res
-                .json::<J>()
+                .bytes()
                 .await
-                .map_err(|err| FetchJsonError::Deserialization(err));
+                .map(|bytes| bytes.to_vec())
+                .map_err(|err| FetchJsonError::ResponseBody(err));
+        }
+        _ => {
+            let res = ipfs_http_client
+                .request(Method::POST, format!("/api/v0/cat?arg={cid}"))
+                .await
+                .map_err(|err| FetchJsonError::RequestConstruction(err))?
+                .send()
+                .await
+                .map_err(|err| FetchJsonError::Request(err))?;
+
+            if let Err(status_err) = res.error_for_status_ref() {
+                return Err(FetchJsonError::Status(
+                    status_err.status().unwrap_or(res.status()),
+                    response_retry_after(&res),
+                ));
+            }
+
+            res.bytes()
+                .await
+                .map(|bytes| bytes.to_vec())
+                .map_err(|err| FetchJsonError::ResponseBody(err))
         }
-        _ => ipfs_http_client
-            .request(Method::POST, format!("/api/v0/cat?arg={cid}"))
-            .await
-            .map_err(|err| FetchJsonError::RequestConstruction(err))?
-            .send()
-            .await
-            .map_err(|err| FetchJsonError::Request(err))?
-            .json::<J>()
-            .await
-            .map_err(|err| FetchJsonError::Deserialization(err)),
     }
 }
 
@@ -54,27 +83,57 @@ pub async fn fetch_json_with_retry<J: DeserializeOwned>(
     cid: String,
     s3_cdn_http_client: Arc<HttpClient>,
     ipfs_http_client: Arc<HttpClient>,
+    cache: Option<Arc<ContentCache>>,
     backoff: ExponentialBackoff,
 ) -> Result<J, FetchJsonError> {
+    let cid = cid.to_lowercase();
+
+    if let Some(cache) = &cache {
+        if let Some(bytes) = cache.get(&cid).await {
+            if let Ok(value) = serde_json::from_slice::<J>(&bytes) {
+                return Ok(value);
+            }
+        }
+    }
+
     let fetch = || async {
-        fetch_json::<J>(
-            cid.clone(),
-            s3_cdn_http_client.clone(),
-            ipfs_http_client.clone(),
-        )
-        .await
-        .map_err(|err| match err {
-            FetchJsonError::RequestConstruction(_) | FetchJsonError::Request(_) => {
-                backoff::Error::Transient {
-                    err,
-                    retry_after: None,
+        fetch_bytes(cid.clone(), s3_cdn_http_client.clone(), ipfs_http_client.clone())
+            .await
+            .map_err(|err| {
+                let permanent_client_error = matches!(
+                    &err,
+                    FetchJsonError::RequestConstruction(inner) if is_permanent_client_error(inner)
+                );
+
+                match err {
+                    _ if permanent_client_error => backoff::Error::Permanent(err),
+                    FetchJsonError::RequestConstruction(_)
+                    | FetchJsonError::Request(_)
+                    | FetchJsonError::ResponseBody(_) => backoff::Error::Transient {
+                        err,
+                        retry_after: None,
+                    },
+                    FetchJsonError::Status(status, retry_after) => {
+                        if is_permanent_status(status) {
+                            backoff::Error::Permanent(err)
+                        } else {
+                            backoff::Error::Transient { err, retry_after }
+                        }
+                    }
+                    FetchJsonError::Deserialization(_) => backoff::Error::Permanent(err),
                 }
-            }
-            FetchJsonError::Deserialization(_) => backoff::Error::Permanent(err),
-        })
+            })
     };
 
-    retry(backoff, fetch).await
+    let bytes = retry(backoff, fetch).await?;
+
+    if let Some(cache) = &cache {
+        // caching is a best-effort optimization: a failure to persist the entry
+        // should not fail the fetch itself
+        let _ = cache.put(&cid, &bytes).await;
+    }
+
+    serde_json::from_slice(&bytes).map_err(|err| FetchJsonError::Deserialization(err))
 }
 
 #[derive(Error, Debug)]
@@ -83,6 +142,8 @@ pub enum StoreCidIpfsError {
     RequestConstruction(#[source] HttpClientError),
     #[error("error while performing cid ipfs store request: {0:?}")]
     Request(#[source] reqwest::Error),
+    #[error("cid ipfs store request returned non-success status {0}")]
+    Status(StatusCode, Option<Duration>),
     #[error("error while deserializing ipfs cid store request: {0:?}")]
     Deserialization(#[source] reqwest::Error),
     #[error("cid mismatch: got {0}, expected {1}")]
@@ -98,14 +159,23 @@ pub async fn store_cid_ipfs(
     cid: String,
     data_uploader_http_client: Arc<HttpClient>,
 ) -> Result<(), StoreCidIpfsError> {
-    let store_response = data_uploader_http_client
+    let res = data_uploader_http_client
         .request(Method::POST, format!("/data/ipfs"))
         .await
         .map_err(|err| StoreCidIpfsError::RequestConstruction(err))?
         .json(&StoreCidRequestResponse { cid: cid.clone() })
         .send()
         .await
-        .map_err(|err| StoreCidIpfsError::Request(err))?
+        .map_err(|err| StoreCidIpfsError::Request(err))?;
+
+    if let Err(status_err) = res.error_for_status_ref() {
+        return Err(StoreCidIpfsError::Status(
+            status_err.status().unwrap_or(res.status()),
+            response_retry_after(&res),
+        ));
+    }
+
+    let store_response = res
         .json::<StoreCidRequestResponse>()
         .await
         .map_err(|err| StoreCidIpfsError::Deserialization(err))?;
@@ -125,15 +195,30 @@ pub async fn store_cid_ipfs_with_retry<J: Serialize>(
     let store = || async {
         store_cid_ipfs(cid.clone(), data_uploader_http_client.clone())
             .await
-            .map_err(|err| match err {
-                StoreCidIpfsError::RequestConstruction(_) | StoreCidIpfsError::Request(_) => {
-                    backoff::Error::Transient {
-                        err,
-                        retry_after: None,
+            .map_err(|err| {
+                let permanent_client_error = matches!(
+                    &err,
+                    StoreCidIpfsError::RequestConstruction(inner) if is_permanent_client_error(inner)
+                );
+
+                match err {
+                    _ if permanent_client_error => backoff::Error::Permanent(err),
+                    StoreCidIpfsError::RequestConstruction(_) | StoreCidIpfsError::Request(_) => {
+                        backoff::Error::Transient {
+                            err,
+                            retry_after: None,
+                        }
+                    }
+                    StoreCidIpfsError::Status(status, retry_after) => {
+                        if is_permanent_status(status) {
+                            backoff::Error::Permanent(err)
+                        } else {
+                            backoff::Error::Transient { err, retry_after }
+                        }
+                    }
+                    StoreCidIpfsError::Deserialization(_) | StoreCidIpfsError::CidMismatch(_, _) => {
+                        backoff::Error::Permanent(err)
                     }
-                }
-                StoreCidIpfsError::Deserialization(_) | StoreCidIpfsError::CidMismatch(_, _) => {
-                    backoff::Error::Permanent(err)
                 }
             })
     };
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use governor::{
     clock::{QuantaClock, QuantaInstant},
@@ -6,6 +6,7 @@ use governor::{
     state::{InMemoryState, NotKeyed},
     RateLimiter,
 };
+use reqwest::StatusCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -16,6 +17,70 @@ pub enum HttpClientError {
     MalformedUrl(String, #[source] url::ParseError),
     #[error("error joining base url {0} with path {1}: {2:?}")]
     PathJoin(String, String, #[source] url::ParseError),
+    #[error("request to host {0} denied by permission gate")]
+    PermissionDenied(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum HostPattern {
+    Any,
+    Exact { host: String, port: Option<u16> },
+    WildcardSubdomain { domain: String, port: Option<u16> },
+}
+
+impl HostPattern {
+    fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        match self {
+            HostPattern::Any => true,
+            HostPattern::Exact { host: expected, port: expected_port } => {
+                expected.eq_ignore_ascii_case(host) && ports_match(*expected_port, port)
+            }
+            HostPattern::WildcardSubdomain { domain, port: expected_port } => {
+                (host.eq_ignore_ascii_case(domain)
+                    || host.to_ascii_lowercase().ends_with(&format!(".{}", domain.to_ascii_lowercase())))
+                    && ports_match(*expected_port, port)
+            }
+        }
+    }
+}
+
+fn ports_match(expected: Option<u16>, actual: Option<u16>) -> bool {
+    match expected {
+        Some(expected) => actual == Some(expected),
+        None => true,
+    }
+}
+
+pub fn is_permanent_client_error(err: &HttpClientError) -> bool {
+    matches!(err, HttpClientError::PermissionDenied(_))
+}
+
+pub fn response_retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?;
+
+    if let Ok(seconds) = value.parse::<f64>() {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return None;
+        }
+        let seconds = seconds.ceil().min(u32::MAX as f64) as u64;
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+pub fn is_permanent_status(status: StatusCode) -> bool {
+    status.is_client_error()
+        && status != StatusCode::REQUEST_TIMEOUT
+        && status != StatusCode::TOO_MANY_REQUESTS
 }
 
 pub struct HttpClient {
@@ -24,6 +89,8 @@ pub struct HttpClient {
     bearer_auth_token: Option<String>,
     rate_limiter:
         Option<RateLimiter<NotKeyed, InMemoryState, QuantaClock, NoOpMiddleware<QuantaInstant>>>,
+    allowed_hosts: Option<Vec<HostPattern>>,
+    denied_hosts: Vec<HostPattern>,
 }
 
 impl<'a> HttpClient {
@@ -41,6 +108,20 @@ impl<'a> HttpClient {
             HttpClientError::PathJoin(self.base_url.as_str().to_owned(), path.to_owned(), err)
         })?;
 
+        if let Some(host) = url.host_str() {
+            let port = url.port_or_known_default();
+
+            if self.denied_hosts.iter().any(|pattern| pattern.matches(host, port)) {
+                return Err(HttpClientError::PermissionDenied(host.to_owned()));
+            }
+
+            if let Some(allowed_hosts) = &self.allowed_hosts {
+                if !allowed_hosts.iter().any(|pattern| pattern.matches(host, port)) {
+                    return Err(HttpClientError::PermissionDenied(host.to_owned()));
+                }
+            }
+        }
+
         if let Some(rate_limiter) = &self.rate_limiter {
             rate_limiter.until_ready().await;
         }
@@ -59,6 +140,15 @@ pub struct HttpClientBuilder<S: AsRef<str>> {
     bearer_auth_token: Option<String>,
     rate_limiter:
         Option<RateLimiter<NotKeyed, InMemoryState, QuantaClock, NoOpMiddleware<QuantaInstant>>>,
+    gzip: bool,
+    brotli: bool,
+    proxy: Option<reqwest::Proxy>,
+    cookie_store: bool,
+    pool_idle_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    trust_dns: bool,
+    allowed_hosts: Option<Vec<HostPattern>>,
+    denied_hosts: Vec<HostPattern>,
 }
 
 impl<S: AsRef<str>> HttpClientBuilder<S> {
@@ -68,13 +158,65 @@ impl<S: AsRef<str>> HttpClientBuilder<S> {
             timeout,
             bearer_auth_token: None,
             rate_limiter: None,
+            gzip: false,
+            brotli: false,
+            proxy: None,
+            cookie_store: false,
+            pool_idle_timeout: None,
+            pool_max_idle_per_host: None,
+            trust_dns: false,
+            allowed_hosts: None,
+            denied_hosts: Vec::new(),
         }
     }
 
     pub fn build(self) -> Result<HttpClient, HttpClientError> {
+        let allowed_hosts = self.allowed_hosts.clone();
+        let denied_hosts = self.denied_hosts.clone();
+
+        let mut client_builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .gzip(self.gzip)
+            .brotli(self.brotli)
+            .cookie_store(self.cookie_store)
+            .trust_dns(self.trust_dns)
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= 10 {
+                    return attempt.error("too many redirects");
+                }
+
+                let host_port = attempt
+                    .url()
+                    .host_str()
+                    .map(|host| (host.to_owned(), attempt.url().port_or_known_default()));
+
+                if let Some((host, port)) = host_port {
+                    if denied_hosts.iter().any(|pattern| pattern.matches(&host, port)) {
+                        return attempt.error(HttpClientError::PermissionDenied(host));
+                    }
+
+                    if let Some(allowed_hosts) = &allowed_hosts {
+                        if !allowed_hosts.iter().any(|pattern| pattern.matches(&host, port)) {
+                            return attempt.error(HttpClientError::PermissionDenied(host));
+                        }
+                    }
+                }
+
+                attempt.follow()
+            }));
+
+        if let Some(proxy) = self.proxy {
+            client_builder = client_builder.proxy(proxy);
+        }
+        if let Some(pool_idle_timeout) = self.pool_idle_timeout {
+            client_builder = client_builder.pool_idle_timeout(pool_idle_timeout);
+        }
+        if let Some(pool_max_idle_per_host) = self.pool_max_idle_per_host {
+            client_builder = client_builder.pool_max_idle_per_host(pool_max_idle_per_host);
+        }
+
         Ok(HttpClient {
-            inner: reqwest::Client::builder()
-                .timeout(self.timeout)
+            inner: client_builder
                 .build()
                 .map_err(|err| HttpClientError::Initialization(err))?,
             base_url: reqwest::Url::parse(self.base_url.as_ref()).map_err(|err| {
@@ -82,6 +224,8 @@ impl<S: AsRef<str>> HttpClientBuilder<S> {
             })?,
             bearer_auth_token: self.bearer_auth_token,
             rate_limiter: self.rate_limiter,
+            allowed_hosts: self.allowed_hosts,
+            denied_hosts: self.denied_hosts,
         })
     }
 
@@ -107,4 +251,49 @@ impl<S: AsRef<str>> HttpClientBuilder<S> {
         self.rate_limiter = Some(rate_limiter);
         self
     }
+
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    pub fn brotli(mut self, enabled: bool) -> Self {
+        self.brotli = enabled;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn cookie_store(mut self, enabled: bool) -> Self {
+        self.cookie_store = enabled;
+        self
+    }
+
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    pub fn trust_dns(mut self, enabled: bool) -> Self {
+        self.trust_dns = enabled;
+        self
+    }
+
+    pub fn allow_host(mut self, pattern: HostPattern) -> Self {
+        self.allowed_hosts.get_or_insert_with(Vec::new).push(pattern);
+        self
+    }
+
+    pub fn deny_host(mut self, pattern: HostPattern) -> Self {
+        self.denied_hosts.push(pattern);
+        self
+    }
 }
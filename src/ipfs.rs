@@ -1,11 +1,25 @@
-use std::sync::Arc;
+use std::{
+    cell::RefCell,
+    io::{self, SeekFrom},
+    sync::Arc,
+    time::Duration,
+};
 
 use backoff::{future::retry, ExponentialBackoff};
-use reqwest::{Body, Method};
-use serde::{de::DeserializeOwned, Deserialize};
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use reqwest::{Body, Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
+use tokio::io::{AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
-use crate::http_client::{HttpClient, HttpClientError};
+use crate::{
+    cache::ContentCache,
+    http_client::{
+        is_permanent_client_error, is_permanent_status, response_retry_after, HttpClient,
+        HttpClientError,
+    },
+};
 
 #[derive(Error, Debug)]
 pub enum FetchJsonError {
@@ -13,43 +27,97 @@ pub enum FetchJsonError {
     RequestConstruction(#[source] HttpClientError),
     #[error("error while performing json fetching request: {0:?}")]
     Request(#[source] reqwest::Error),
+    #[error("json fetch request returned non-success status {0}")]
+    Status(StatusCode, Option<Duration>),
+    #[error("error while reading json fetch response body: {0:?}")]
+    ResponseBody(#[source] reqwest::Error),
     #[error("error while deserializing json fetch response: {0:?}")]
-    Deserialization(#[source] reqwest::Error),
+    Deserialization(#[source] serde_json::Error),
 }
 
-pub async fn fetch_json_with_retry<J: DeserializeOwned>(
+async fn fetch_bytes(
     cid: String,
     ipfs_http_client: Arc<HttpClient>,
     backoff: ExponentialBackoff,
-) -> Result<J, FetchJsonError> {
+) -> Result<Vec<u8>, FetchJsonError> {
     let fetch = || async {
-        ipfs_http_client
+        let res = ipfs_http_client
             .request(Method::POST, format!("/api/v0/cat?arg={cid}"))
             .await
-            .map_err(|err| backoff::Error::Transient {
-                err: FetchJsonError::RequestConstruction(err),
-                retry_after: None,
+            .map_err(|err| {
+                if is_permanent_client_error(&err) {
+                    backoff::Error::Permanent(FetchJsonError::RequestConstruction(err))
+                } else {
+                    backoff::Error::Transient {
+                        err: FetchJsonError::RequestConstruction(err),
+                        retry_after: None,
+                    }
+                }
             })?
             .send()
             .await
             .map_err(|err| backoff::Error::Transient {
                 err: FetchJsonError::Request(err),
                 retry_after: None,
-            })?
-            .json::<J>()
-            .await
-            .map_err(|err| backoff::Error::Permanent(FetchJsonError::Deserialization(err)))
+            })?;
+
+        if let Err(status_err) = res.error_for_status_ref() {
+            let status = status_err.status().unwrap_or(res.status());
+            let retry_after = response_retry_after(&res);
+            let err = FetchJsonError::Status(status, retry_after);
+            return Err(if is_permanent_status(status) {
+                backoff::Error::Permanent(err)
+            } else {
+                backoff::Error::Transient { err, retry_after }
+            });
+        }
+
+        res.bytes().await.map(|bytes| bytes.to_vec()).map_err(|err| {
+            backoff::Error::Transient {
+                err: FetchJsonError::ResponseBody(err),
+                retry_after: None,
+            }
+        })
     };
 
     retry(backoff, fetch).await
 }
 
+pub async fn fetch_json_with_retry<J: DeserializeOwned>(
+    cid: String,
+    ipfs_http_client: Arc<HttpClient>,
+    cache: Option<Arc<ContentCache>>,
+    backoff: ExponentialBackoff,
+) -> Result<J, FetchJsonError> {
+    let cid = cid.to_lowercase();
+
+    if let Some(cache) = &cache {
+        if let Some(bytes) = cache.get(&cid).await {
+            if let Ok(value) = serde_json::from_slice::<J>(&bytes) {
+                return Ok(value);
+            }
+        }
+    }
+
+    let bytes = fetch_bytes(cid.clone(), ipfs_http_client, backoff).await?;
+
+    if let Some(cache) = &cache {
+        // caching is a best-effort optimization: a failure to persist the entry
+        // should not fail the fetch itself
+        let _ = cache.put(&cid, &bytes).await;
+    }
+
+    serde_json::from_slice(&bytes).map_err(FetchJsonError::Deserialization)
+}
+
 #[derive(Error, Debug)]
 pub enum IpfsPinError {
     #[error("error while constructing pin request: {0:?}")]
     RequestConstruction(#[source] HttpClientError),
     #[error("error while performing pin request: {0:?}")]
     Request(#[source] reqwest::Error),
+    #[error("pin request returned non-success status {0}")]
+    Status(StatusCode, Option<Duration>),
     #[error("error while deserializing pin request: {0:?}")]
     Deserialization(#[source] reqwest::Error),
     #[error("expected 1 pinned cid, got {0}")]
@@ -78,6 +146,11 @@ pub async fn pin_cid_with_retry(
             .await
         {
             Ok(req) => req,
+            Err(err) if is_permanent_client_error(&err) => {
+                return Err(backoff::Error::Permanent(IpfsPinError::RequestConstruction(
+                    err,
+                )));
+            }
             Err(err) => {
                 return Err(backoff::Error::Transient {
                     err: IpfsPinError::RequestConstruction(err),
@@ -96,6 +169,17 @@ pub async fn pin_cid_with_retry(
             }
         };
 
+        if let Err(status_err) = response.error_for_status_ref() {
+            let status = status_err.status().unwrap_or(response.status());
+            let retry_after = response_retry_after(&response);
+            let err = IpfsPinError::Status(status, retry_after);
+            return Err(if is_permanent_status(status) {
+                backoff::Error::Permanent(err)
+            } else {
+                backoff::Error::Transient { err, retry_after }
+            });
+        }
+
         // convert pin response to json
         let PinResponse { pins } = match response.json::<PinResponse>().await {
             Ok(res) => res,
@@ -126,18 +210,49 @@ pub async fn pin_cid_with_retry(
     retry(backoff, operation).await
 }
 
+pub const DEFAULT_MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+pub const MIN_MULTIPART_PART_SIZE: usize = 5 * 1024 * 1024;
+
 #[derive(Error, Debug)]
 pub enum Web3StoragePinError {
     #[error("error while constructing web3.storage car fetching request: {0:?}")]
     GetCarRequestConstruction(#[source] HttpClientError),
     #[error("error while performing car fetching request: {0:?}")]
     GetCarRequest(#[source] reqwest::Error),
+    #[error("car fetching request returned non-success status {0}")]
+    GetCarStatus(StatusCode, Option<Duration>),
     #[error("error while constructing web3.storage car upload request: {0:?}")]
     UploadCarRequestConstruction(#[source] HttpClientError),
     #[error("error while performing car upload request: {0:?}")]
     UploadCarRequest(#[source] reqwest::Error),
+    #[error("car upload request returned non-success status {0}")]
+    UploadCarStatus(StatusCode, Option<Duration>),
     #[error("error while deserializing car upload request: {0:?}")]
     UploadCarDeserialization(#[source] reqwest::Error),
+    #[error("error while constructing web3.storage multipart initiate request: {0:?}")]
+    MultipartInitiateRequestConstruction(#[source] HttpClientError),
+    #[error("error while performing multipart initiate request: {0:?}")]
+    MultipartInitiateRequest(#[source] reqwest::Error),
+    #[error("multipart initiate request returned non-success status {0}")]
+    MultipartInitiateStatus(StatusCode, Option<Duration>),
+    #[error("error while deserializing multipart initiate request: {0:?}")]
+    MultipartInitiateDeserialization(#[source] reqwest::Error),
+    #[error("error while constructing web3.storage multipart part upload request: {0:?}")]
+    MultipartUploadPartRequestConstruction(#[source] HttpClientError),
+    #[error("error while performing multipart part upload request: {0:?}")]
+    MultipartUploadPartRequest(#[source] reqwest::Error),
+    #[error("multipart part upload request returned non-success status {0}")]
+    MultipartUploadPartStatus(StatusCode, Option<Duration>),
+    #[error("error while deserializing multipart part upload request: {0:?}")]
+    MultipartUploadPartDeserialization(#[source] reqwest::Error),
+    #[error("error while constructing web3.storage multipart complete request: {0:?}")]
+    MultipartCompleteRequestConstruction(#[source] HttpClientError),
+    #[error("error while performing multipart complete request: {0:?}")]
+    MultipartCompleteRequest(#[source] reqwest::Error),
+    #[error("multipart complete request returned non-success status {0}")]
+    MultipartCompleteStatus(StatusCode, Option<Duration>),
+    #[error("error while deserializing multipart complete request: {0:?}")]
+    MultipartCompleteDeserialization(#[source] reqwest::Error),
     #[error("cid mismatch between original cid and uploaded cid: got {0}, expected {1}")]
     CidMismatch(String, String),
 }
@@ -147,12 +262,334 @@ pub struct CARUploadResponse {
     cid: String,
 }
 
+#[derive(Deserialize)]
+struct InitiateMultipartResponse {
+    upload_id: String,
+}
+
+#[derive(Serialize, Clone)]
+struct CompletedPart {
+    part_number: u32,
+    etag: String,
+}
+
+#[derive(Deserialize)]
+struct UploadPartResponse {
+    etag: String,
+}
+
+#[derive(Serialize)]
+struct CompleteMultipartRequest {
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+}
+
+async fn initiate_multipart(
+    web3_storage_http_client: &HttpClient,
+) -> Result<String, Web3StoragePinError> {
+    let res = web3_storage_http_client
+        .request(Method::POST, "/car/multipart")
+        .await
+        .map_err(|err| Web3StoragePinError::MultipartInitiateRequestConstruction(err))?
+        .send()
+        .await
+        .map_err(|err| Web3StoragePinError::MultipartInitiateRequest(err))?;
+
+    if let Err(status_err) = res.error_for_status_ref() {
+        return Err(Web3StoragePinError::MultipartInitiateStatus(
+            status_err.status().unwrap_or(res.status()),
+            response_retry_after(&res),
+        ));
+    }
+
+    res.json::<InitiateMultipartResponse>()
+        .await
+        .map(|res| res.upload_id)
+        .map_err(|err| Web3StoragePinError::MultipartInitiateDeserialization(err))
+}
+
+async fn initiate_multipart_with_retry(
+    web3_storage_http_client: &HttpClient,
+    backoff: ExponentialBackoff,
+) -> Result<String, Web3StoragePinError> {
+    let initiate = || async {
+        initiate_multipart(web3_storage_http_client)
+            .await
+            .map_err(|err| match err {
+                Web3StoragePinError::MultipartInitiateRequestConstruction(ref inner)
+                    if is_permanent_client_error(inner) =>
+                {
+                    backoff::Error::Permanent(err)
+                }
+                Web3StoragePinError::MultipartInitiateStatus(status, retry_after) => {
+                    if is_permanent_status(status) {
+                        backoff::Error::Permanent(err)
+                    } else {
+                        backoff::Error::Transient { err, retry_after }
+                    }
+                }
+                _ => backoff::Error::Transient {
+                    err,
+                    retry_after: None,
+                },
+            })
+    };
+
+    retry(backoff, initiate).await
+}
+
+async fn upload_part(
+    web3_storage_http_client: &HttpClient,
+    upload_id: &str,
+    part_number: u32,
+    part: Bytes,
+) -> Result<String, Web3StoragePinError> {
+    let res = web3_storage_http_client
+        .request(
+            Method::PUT,
+            format!("/car/multipart/{upload_id}/{part_number}"),
+        )
+        .await
+        .map_err(|err| Web3StoragePinError::MultipartUploadPartRequestConstruction(err))?
+        .body(part)
+        .send()
+        .await
+        .map_err(|err| Web3StoragePinError::MultipartUploadPartRequest(err))?;
+
+    if let Err(status_err) = res.error_for_status_ref() {
+        return Err(Web3StoragePinError::MultipartUploadPartStatus(
+            status_err.status().unwrap_or(res.status()),
+            response_retry_after(&res),
+        ));
+    }
+
+    res.json::<UploadPartResponse>()
+        .await
+        .map(|res| res.etag)
+        .map_err(|err| Web3StoragePinError::MultipartUploadPartDeserialization(err))
+}
+
+// retries only the single failed part, rather than the whole upload, on transient errors
+async fn upload_part_with_retry(
+    web3_storage_http_client: &HttpClient,
+    upload_id: &str,
+    part_number: u32,
+    part: Bytes,
+    backoff: ExponentialBackoff,
+) -> Result<String, Web3StoragePinError> {
+    let upload = || async {
+        upload_part(web3_storage_http_client, upload_id, part_number, part.clone())
+            .await
+            .map_err(|err| match err {
+                Web3StoragePinError::MultipartUploadPartRequestConstruction(ref inner)
+                    if is_permanent_client_error(inner) =>
+                {
+                    backoff::Error::Permanent(err)
+                }
+                Web3StoragePinError::MultipartUploadPartStatus(status, retry_after) => {
+                    if is_permanent_status(status) {
+                        backoff::Error::Permanent(err)
+                    } else {
+                        backoff::Error::Transient { err, retry_after }
+                    }
+                }
+                _ => backoff::Error::Transient {
+                    err,
+                    retry_after: None,
+                },
+            })
+    };
+
+    retry(backoff, upload).await
+}
+
+async fn complete_multipart(
+    web3_storage_http_client: &HttpClient,
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+) -> Result<CARUploadResponse, Web3StoragePinError> {
+    let res = web3_storage_http_client
+        .request(Method::POST, "/car/multipart/complete")
+        .await
+        .map_err(|err| Web3StoragePinError::MultipartCompleteRequestConstruction(err))?
+        .json(&CompleteMultipartRequest { upload_id, parts })
+        .send()
+        .await
+        .map_err(|err| Web3StoragePinError::MultipartCompleteRequest(err))?;
+
+    if let Err(status_err) = res.error_for_status_ref() {
+        return Err(Web3StoragePinError::MultipartCompleteStatus(
+            status_err.status().unwrap_or(res.status()),
+            response_retry_after(&res),
+        ));
+    }
+
+    res.json::<CARUploadResponse>()
+        .await
+        .map_err(|err| Web3StoragePinError::MultipartCompleteDeserialization(err))
+}
+
+async fn complete_multipart_with_retry(
+    web3_storage_http_client: &HttpClient,
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+    backoff: ExponentialBackoff,
+) -> Result<CARUploadResponse, Web3StoragePinError> {
+    let complete = || async {
+        complete_multipart(web3_storage_http_client, upload_id.clone(), parts.clone())
+            .await
+            .map_err(|err| match err {
+                Web3StoragePinError::MultipartCompleteRequestConstruction(ref inner)
+                    if is_permanent_client_error(inner) =>
+                {
+                    backoff::Error::Permanent(err)
+                }
+                Web3StoragePinError::MultipartCompleteStatus(status, retry_after) => {
+                    if is_permanent_status(status) {
+                        backoff::Error::Permanent(err)
+                    } else {
+                        backoff::Error::Transient { err, retry_after }
+                    }
+                }
+                _ => backoff::Error::Transient {
+                    err,
+                    retry_after: None,
+                },
+            })
+    };
+
+    retry(backoff, complete).await
+}
+
+async fn fill_part(
+    car_stream: &mut (impl futures_util::Stream<Item = Result<Bytes, reqwest::Error>> + Unpin),
+    buffer: &mut BytesMut,
+    part_size: usize,
+) -> Result<bool, Web3StoragePinError> {
+    while buffer.len() < part_size {
+        match car_stream.next().await {
+            Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+            Some(Err(err)) => return Err(Web3StoragePinError::GetCarRequest(err)),
+            None => return Ok(true),
+        }
+    }
+
+    Ok(false)
+}
+
+async fn upload_car(
+    web3_storage_http_client: Arc<HttpClient>,
+    mut car_stream: impl futures_util::Stream<Item = Result<Bytes, reqwest::Error>> + Unpin,
+    part_size: usize,
+    upload_id: &RefCell<Option<String>>,
+    completed_parts: &RefCell<Vec<CompletedPart>>,
+    backoff: ExponentialBackoff,
+) -> Result<CARUploadResponse, Web3StoragePinError> {
+    if upload_id.borrow().is_none() {
+        let mut buffer = BytesMut::with_capacity(part_size);
+        let exhausted = fill_part(&mut car_stream, &mut buffer, part_size).await?;
+        let first_part = buffer.split().freeze();
+
+        if exhausted {
+            let res = web3_storage_http_client
+                .request(Method::POST, "/car")
+                .await
+                .map_err(|err| Web3StoragePinError::UploadCarRequestConstruction(err))?
+                .body(Body::from(first_part))
+                .send()
+                .await
+                .map_err(|err| Web3StoragePinError::UploadCarRequest(err))?;
+
+            if let Err(status_err) = res.error_for_status_ref() {
+                return Err(Web3StoragePinError::UploadCarStatus(
+                    status_err.status().unwrap_or(res.status()),
+                    response_retry_after(&res),
+                ));
+            }
+
+            return res
+                .json::<CARUploadResponse>()
+                .await
+                .map_err(|err| Web3StoragePinError::UploadCarDeserialization(err));
+        }
+
+        let new_upload_id = initiate_multipart_with_retry(&web3_storage_http_client, backoff.clone()).await?;
+
+        let etag = upload_part_with_retry(
+            &web3_storage_http_client,
+            &new_upload_id,
+            1,
+            first_part,
+            backoff.clone(),
+        )
+        .await?;
+        completed_parts.borrow_mut().push(CompletedPart {
+            part_number: 1,
+            etag,
+        });
+        *upload_id.borrow_mut() = Some(new_upload_id);
+    } else {
+        // resuming a previously-initiated upload: skip the bytes of parts already
+        // uploaded instead of re-sending them
+        let resume_count = completed_parts.borrow().len();
+        for _ in 0..resume_count {
+            let mut discarded = BytesMut::with_capacity(part_size);
+            fill_part(&mut car_stream, &mut discarded, part_size).await?;
+        }
+    }
+
+    let current_upload_id = upload_id
+        .borrow()
+        .clone()
+        .expect("multipart upload initiated above");
+
+    loop {
+        let mut buffer = BytesMut::with_capacity(part_size);
+        let exhausted = fill_part(&mut car_stream, &mut buffer, part_size).await?;
+
+        if buffer.is_empty() {
+            break;
+        }
+
+        let part_number = completed_parts.borrow().len() as u32 + 1;
+        let part = buffer.split().freeze();
+        let etag = upload_part_with_retry(
+            &web3_storage_http_client,
+            &current_upload_id,
+            part_number,
+            part,
+            backoff.clone(),
+        )
+        .await?;
+        completed_parts.borrow_mut().push(CompletedPart { part_number, etag });
+
+        if exhausted {
+            break;
+        }
+    }
+
+    let parts = completed_parts.borrow().clone();
+    complete_multipart_with_retry(&web3_storage_http_client, current_upload_id, parts, backoff)
+        .await
+}
+
 pub async fn pin_cid_web3_storage_with_retry(
     cid: String,
     ipfs_http_client: Arc<HttpClient>,
     web3_storage_http_client: Arc<HttpClient>,
+    part_size: Option<usize>,
     backoff: ExponentialBackoff,
 ) -> Result<(), Web3StoragePinError> {
+    let part_size = part_size
+        .unwrap_or(DEFAULT_MULTIPART_PART_SIZE)
+        .max(MIN_MULTIPART_PART_SIZE);
+
+    // carried across retries so a failure after parts have already been uploaded
+    // (e.g. in complete_multipart) resumes the same multipart upload instead of
+    // re-uploading everything under a new upload_id
+    let upload_id: RefCell<Option<String>> = RefCell::new(None);
+    let completed_parts: RefCell<Vec<CompletedPart>> = RefCell::new(Vec::new());
+
     let operation = || async {
         let cid = cid.clone();
 
@@ -163,9 +600,15 @@ pub async fn pin_cid_web3_storage_with_retry(
                 format!("/api/v0/dag/export?arg={cid}&progress=false"),
             )
             .await
-            .map_err(|err| backoff::Error::Transient {
-                err: Web3StoragePinError::GetCarRequestConstruction(err),
-                retry_after: None,
+            .map_err(|err| {
+                if is_permanent_client_error(&err) {
+                    backoff::Error::Permanent(Web3StoragePinError::GetCarRequestConstruction(err))
+                } else {
+                    backoff::Error::Transient {
+                        err: Web3StoragePinError::GetCarRequestConstruction(err),
+                        retry_after: None,
+                    }
+                }
             })?
             .send()
             .await
@@ -174,37 +617,187 @@ pub async fn pin_cid_web3_storage_with_retry(
                 retry_after: None,
             })?;
 
-        // upload car to web3.storage
-        let car_upload_response = web3_storage_http_client
-            .request(Method::POST, "/car")
-            .await
-            .map_err(|err| backoff::Error::Transient {
-                err: Web3StoragePinError::UploadCarRequestConstruction(err),
+        if let Err(status_err) = car_response.error_for_status_ref() {
+            let status = status_err.status().unwrap_or(car_response.status());
+            let retry_after = response_retry_after(&car_response);
+            let err = Web3StoragePinError::GetCarStatus(status, retry_after);
+            return Err(if is_permanent_status(status) {
+                backoff::Error::Permanent(err)
+            } else {
+                backoff::Error::Transient { err, retry_after }
+            });
+        }
+
+        // upload car to web3.storage, chunked if it's at least a single part in size
+        let car_upload_response = upload_car(
+            web3_storage_http_client.clone(),
+            car_response.bytes_stream(),
+            part_size,
+            &upload_id,
+            &completed_parts,
+            backoff.clone(),
+        )
+        .await
+        .map_err(|err| match err {
+            Web3StoragePinError::GetCarRequestConstruction(ref inner)
+            | Web3StoragePinError::UploadCarRequestConstruction(ref inner)
+            | Web3StoragePinError::MultipartInitiateRequestConstruction(ref inner)
+            | Web3StoragePinError::MultipartUploadPartRequestConstruction(ref inner)
+            | Web3StoragePinError::MultipartCompleteRequestConstruction(ref inner)
+                if is_permanent_client_error(inner) =>
+            {
+                backoff::Error::Permanent(err)
+            }
+            Web3StoragePinError::GetCarStatus(status, retry_after)
+            | Web3StoragePinError::UploadCarStatus(status, retry_after)
+            | Web3StoragePinError::MultipartInitiateStatus(status, retry_after)
+            | Web3StoragePinError::MultipartUploadPartStatus(status, retry_after)
+            | Web3StoragePinError::MultipartCompleteStatus(status, retry_after) => {
+                if is_permanent_status(status) {
+                    backoff::Error::Permanent(err)
+                } else {
+                    backoff::Error::Transient { err, retry_after }
+                }
+            }
+            Web3StoragePinError::UploadCarDeserialization(_)
+            | Web3StoragePinError::MultipartInitiateDeserialization(_)
+            | Web3StoragePinError::MultipartUploadPartDeserialization(_)
+            | Web3StoragePinError::MultipartCompleteDeserialization(_) => {
+                backoff::Error::Permanent(err)
+            }
+            _ => backoff::Error::Transient {
+                err,
                 retry_after: None,
+            },
+        })?;
+
+        if car_upload_response.cid != *cid {
+            return Err(backoff::Error::Permanent(Web3StoragePinError::CidMismatch(
+                car_upload_response.cid,
+                cid,
+            )));
+        }
+
+        Ok(())
+    };
+
+    retry(backoff.clone(), operation).await
+}
+
+#[derive(Error, Debug)]
+pub enum DownloadCidError {
+    #[error("error while constructing cid download request: {0:?}")]
+    RequestConstruction(#[source] HttpClientError),
+    #[error("error while performing cid download request: {0:?}")]
+    Request(#[source] reqwest::Error),
+    #[error("cid download request returned non-success status {0}")]
+    Status(StatusCode, Option<Duration>),
+    #[error("error while reading cid download response body: {0:?}")]
+    ResponseBody(#[source] reqwest::Error),
+    #[error("error while writing cid download response body to destination: {0:?}")]
+    Write(#[source] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DownloadProgress {
+    pub bytes_received: u64,
+    pub content_length: Option<u64>,
+    pub accepts_ranges: bool,
+}
+
+// destination is exclusively owned by this retry loop (never borrowed concurrently),
+// so holding its RefCell borrow across the seek/write awaits below is not a hazard
+#[allow(clippy::await_holding_refcell_ref)]
+pub async fn download_cid_with_retry<W: AsyncWrite + AsyncSeek + Unpin>(
+    cid: String,
+    ipfs_http_client: Arc<HttpClient>,
+    destination: &mut W,
+    backoff: ExponentialBackoff,
+) -> Result<DownloadProgress, DownloadCidError> {
+    let cid = cid.to_lowercase();
+    let destination = RefCell::new(destination);
+    let bytes_received = RefCell::new(0u64);
+    let accepts_ranges = RefCell::new(false);
+    let content_length = RefCell::new(None);
+
+    let download = || async {
+        let res = ipfs_http_client
+            .request(Method::POST, format!("/api/v0/cat?arg={cid}"))
+            .await
+            .map_err(|err| {
+                if is_permanent_client_error(&err) {
+                    backoff::Error::Permanent(DownloadCidError::RequestConstruction(err))
+                } else {
+                    backoff::Error::Transient {
+                        err: DownloadCidError::RequestConstruction(err),
+                        retry_after: None,
+                    }
+                }
             })?
-            .body(Body::wrap_stream(car_response.bytes_stream()))
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-", *bytes_received.borrow()),
+            )
             .send()
             .await
             .map_err(|err| backoff::Error::Transient {
-                err: Web3StoragePinError::UploadCarRequest(err),
+                err: DownloadCidError::Request(err),
                 retry_after: None,
-            })?
-            .json::<CARUploadResponse>()
-            .await
-            .map_err(|err| backoff::Error::Transient {
-                err: Web3StoragePinError::UploadCarDeserialization(err),
+            })?;
+
+        if let Err(status_err) = res.error_for_status_ref() {
+            let status = status_err.status().unwrap_or(res.status());
+            let retry_after = response_retry_after(&res);
+            let err = DownloadCidError::Status(status, retry_after);
+            return Err(if is_permanent_status(status) {
+                backoff::Error::Permanent(err)
+            } else {
+                backoff::Error::Transient { err, retry_after }
+            });
+        }
+
+        *accepts_ranges.borrow_mut() = res
+            .headers()
+            .get(reqwest::header::ACCEPT_RANGES)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("bytes"));
+        *content_length.borrow_mut() = res.content_length();
+
+        // the server ignored our Range header and sent the full body again: rewind
+        // and fall back to a full re-fetch instead of appending past what we wrote
+        if *bytes_received.borrow() > 0 && res.status() != StatusCode::PARTIAL_CONTENT {
+            destination
+                .borrow_mut()
+                .seek(SeekFrom::Start(0))
+                .await
+                .map_err(|err| backoff::Error::Permanent(DownloadCidError::Write(err)))?;
+            *bytes_received.borrow_mut() = 0;
+        }
+
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|err| backoff::Error::Transient {
+                err: DownloadCidError::ResponseBody(err),
                 retry_after: None,
             })?;
 
-        if car_upload_response.cid != *cid {
-            return Err(backoff::Error::Permanent(Web3StoragePinError::CidMismatch(
-                car_upload_response.cid,
-                cid,
-            )));
+            destination
+                .borrow_mut()
+                .write_all(&chunk)
+                .await
+                .map_err(|err| backoff::Error::Permanent(DownloadCidError::Write(err)))?;
+
+            *bytes_received.borrow_mut() += chunk.len() as u64;
         }
 
         Ok(())
     };
 
-    retry(backoff, operation).await
+    retry(backoff, download).await?;
+
+    Ok(DownloadProgress {
+        bytes_received: bytes_received.into_inner(),
+        content_length: content_length.into_inner(),
+        accepts_ranges: accepts_ranges.into_inner(),
+    })
 }